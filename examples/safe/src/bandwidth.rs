@@ -0,0 +1,105 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Per-connection bandwidth accounting, injected into the transport with [`Transport::map`]
+//! right below the noise/yamux upgrade so every byte that actually hits the wire is counted.
+//!
+//! The counters are a single global aggregate across every connection, so they show overall
+//! node throughput but can't attribute bytes to a particular peer or transport (relay-circuit
+//! vs. direct TCP); splitting that out would mean keying [`BandwidthSinks`] per path.
+
+use futures::io::{AsyncRead, AsyncWrite};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+/// Cumulative inbound/outbound byte counters, shared between the transport and whoever wants to
+/// poll them (e.g. a periodic logging task).
+#[derive(Default)]
+pub struct BandwidthSinks {
+    inbound: AtomicU64,
+    outbound: AtomicU64,
+}
+
+impl BandwidthSinks {
+    pub fn total_inbound(&self) -> u64 {
+        self.inbound.load(Ordering::Relaxed)
+    }
+
+    pub fn total_outbound(&self) -> u64 {
+        self.outbound.load(Ordering::Relaxed)
+    }
+}
+
+/// Wraps a connection's `AsyncRead`/`AsyncWrite` implementation, adding every byte read or
+/// written to the shared [`BandwidthSinks`].
+pub struct BandwidthConnec<S> {
+    inner: S,
+    sinks: Arc<BandwidthSinks>,
+}
+
+impl<S> BandwidthConnec<S> {
+    pub fn new(inner: S, sinks: Arc<BandwidthSinks>) -> Self {
+        Self { inner, sinks }
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for BandwidthConnec<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let num_bytes = match Pin::new(&mut self.inner).poll_read(cx, buf) {
+            Poll::Ready(Ok(n)) => n,
+            other => return other,
+        };
+        self.sinks
+            .inbound
+            .fetch_add(num_bytes as u64, Ordering::Relaxed);
+        Poll::Ready(Ok(num_bytes))
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for BandwidthConnec<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let num_bytes = match Pin::new(&mut self.inner).poll_write(cx, buf) {
+            Poll::Ready(Ok(n)) => n,
+            other => return other,
+        };
+        self.sinks
+            .outbound
+            .fetch_add(num_bytes as u64, Ordering::Relaxed);
+        Poll::Ready(Ok(num_bytes))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_close(cx)
+    }
+}
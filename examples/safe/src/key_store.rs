@@ -0,0 +1,69 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Loads the node's identity keypair from `--key-file`, generating and persisting a fresh one
+//! the first time the example is run so the `PeerId` is stable across restarts.
+
+use libp2p::identity::Keypair;
+use std::error::Error;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+
+pub fn load_or_generate(path: &Path) -> Result<Keypair, Box<dyn Error>> {
+    if path.exists() {
+        let bytes = fs::read(path)?;
+        let keypair = Keypair::from_protobuf_encoding(&bytes)?;
+        log::info!("loaded node identity from {}", path.display());
+        return Ok(keypair);
+    }
+
+    let keypair = Keypair::generate_ed25519();
+
+    if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        fs::create_dir_all(parent)?;
+    }
+    // On Unix, created with owner-only permissions from the start, so the private key is never
+    // briefly world-readable between the write and a follow-up chmod. Elsewhere the file gets
+    // the platform's default permissions.
+    let mut file = create_restricted(path)?;
+    file.write_all(&keypair.to_protobuf_encoding()?)?;
+
+    log::info!("generated new node identity, saved to {}", path.display());
+    Ok(keypair)
+}
+
+#[cfg(unix)]
+fn create_restricted(path: &Path) -> std::io::Result<fs::File> {
+    use std::os::unix::fs::OpenOptionsExt;
+    fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(path)
+}
+
+#[cfg(not(unix))]
+fn create_restricted(path: &Path) -> std::io::Result<fs::File> {
+    fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(path)
+}
@@ -18,26 +18,53 @@
 // FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
 // DEALINGS IN THE SOFTWARE.
 
+mod bandwidth;
+mod key_store;
+mod network_load;
+mod peer_manager;
+
 use clap::Parser;
-use futures::StreamExt;
+use futures::future::FutureExt;
+use futures::{select, StreamExt};
+use futures_timer::Delay;
+use libp2p::connection_limits::{self, ConnectionLimits};
+use libp2p::core::muxing::StreamMuxerBox;
+use libp2p::core::transport::OrTransport;
 use libp2p::multiaddr::Protocol;
-use libp2p::swarm::keep_alive;
 use libp2p::swarm::NetworkBehaviour;
 use libp2p::{
     autonat::Event as AutoNatEvent,
     autonat::InboundProbeEvent,
+    autonat::NatStatus,
     core::upgrade,
+    dcutr,
     identify::Event as IdentifyEvent,
-    identity, noise,
-    swarm::{SwarmBuilder, SwarmEvent},
+    identity, noise, relay,
+    swarm::{ConnectionId, SwarmBuilder, SwarmEvent},
     tcp, yamux, PeerId,
 };
-use libp2p::{Multiaddr, Swarm, Transport};
+use libp2p::{rendezvous, Multiaddr, Swarm, Transport};
+use network_load::NetworkLoad;
+use peer_manager::PeerManager;
+use std::collections::HashSet;
 use std::error::Error;
 use std::net::Ipv4Addr;
-use std::time::Duration;
+use std::path::PathBuf;
+use std::process;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use void::Void;
 
+/// How often the bandwidth accounting task logs total and per-second up/down rates.
+const BANDWIDTH_LOG_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Default cap on established connections to a single peer.
+const MAX_CONNECTIONS_PER_PEER: u32 = 4;
+
+/// How far over `--target-peers` the peer manager lets the connected count grow before it starts
+/// closing connections to the least-useful peers.
+const PEER_EXCESS_FACTOR: f64 = 0.2;
+
 #[derive(Parser, Debug)]
 #[clap()]
 struct Opt {
@@ -53,6 +80,55 @@ struct Opt {
         value_delimiter = ','
     )]
     pub peers: Vec<Multiaddr>,
+
+    /// Rendezvous point to register with, and to discover peers through
+    #[clap(long, value_name = "multiaddr")]
+    rendezvous: Option<Multiaddr>,
+
+    /// Namespace to register ourselves under / discover peers under at the rendezvous point
+    #[clap(long, default_value = "safe")]
+    namespace: String,
+
+    #[clap(subcommand)]
+    command: Option<Command>,
+
+    /// Connection and keep-alive aggressiveness, from 1 (minimize churn, probe rarely) to 5
+    /// (keep connections warm, probe often)
+    #[clap(
+        long,
+        default_value_t = 3,
+        value_parser = clap::value_parser!(u8).range(1..=5),
+    )]
+    network_load: u8,
+
+    /// Maximum number of established connections, in either direction, in total
+    #[clap(long)]
+    max_connections: Option<u32>,
+
+    /// Maximum number of simultaneously dialing (pending) connections, in either direction
+    #[clap(long)]
+    max_pending: Option<u32>,
+
+    /// Maximum number of established connections to a single peer
+    #[clap(long, default_value_t = MAX_CONNECTIONS_PER_PEER)]
+    max_connections_per_peer: u32,
+
+    /// Target number of connected peers; once the actual count exceeds this by more than the
+    /// peer manager's excess factor, the least-useful peers are pruned
+    #[clap(long, default_value_t = 50)]
+    target_peers: usize,
+
+    /// Path to the node's private key, protobuf-encoded. Generated on first run and reused on
+    /// every subsequent one, so the PeerId stays stable across restarts
+    #[clap(long, value_name = "path")]
+    key_file: Option<PathBuf>,
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum Command {
+    /// Discover peers registered under `--namespace` at the `--rendezvous` point, print each
+    /// one's PeerId and addresses, then exit.
+    ListPeers,
 }
 
 #[async_std::main]
@@ -65,19 +141,52 @@ async fn main() -> Result<(), Box<dyn Error>> {
     let opt = Opt::parse();
     log::info!("opts: {opt:?}");
 
-    let local_key = identity::Keypair::generate_ed25519();
+    if matches!(opt.command, Some(Command::ListPeers)) && opt.rendezvous.is_none() {
+        return Err(
+            "list-peers requires --rendezvous <multiaddr> to discover peers through".into(),
+        );
+    }
+
+    let local_key = match &opt.key_file {
+        Some(path) => key_store::load_or_generate(path)?,
+        None => identity::Keypair::generate_ed25519(),
+    };
     let local_peer_id = PeerId::from(local_key.public());
 
+    let (relay_transport, relay_client) = relay::client::new(local_peer_id);
+    let bandwidth_sinks = Arc::new(bandwidth::BandwidthSinks::default());
+    let network_load = NetworkLoad::from(opt.network_load);
+
     let mut swarm = {
-        let transport = tcp::async_io::Transport::default()
+        let tcp_transport = tcp::async_io::Transport::default();
+        let transport = OrTransport::new(relay_transport, tcp_transport)
+            .map({
+                let bandwidth_sinks = bandwidth_sinks.clone();
+                move |output, _| bandwidth::BandwidthConnec::new(output, bandwidth_sinks.clone())
+            })
             .upgrade(upgrade::Version::V1Lazy)
             .authenticate(noise::Config::new(&local_key)?)
             .multiplex(yamux::Config::default())
+            .map(|(peer_id, muxer), _| (peer_id, StreamMuxerBox::new(muxer)))
             .boxed();
 
-        let behaviour = Behaviour::new(local_key.public());
+        let connection_limits = ConnectionLimits::default()
+            .with_max_established_per_peer(Some(opt.max_connections_per_peer))
+            .with_max_established(opt.max_connections)
+            .with_max_pending_incoming(opt.max_pending)
+            .with_max_pending_outgoing(opt.max_pending);
 
-        SwarmBuilder::with_async_std_executor(transport, behaviour, local_peer_id).build()
+        let behaviour = Behaviour::new(
+            &local_key,
+            relay_client,
+            bandwidth_sinks.clone(),
+            network_load,
+            connection_limits,
+        );
+
+        SwarmBuilder::with_async_std_executor(transport, behaviour, local_peer_id)
+            .idle_connection_timeout(network_load.idle_connection_timeout)
+            .build()
     };
 
     // Start listening
@@ -85,9 +194,51 @@ async fn main() -> Result<(), Box<dyn Error>> {
     swarm.listen_on(listen_addr)?;
 
     let mut bootstrapped = false;
+    // The bootstrap peer we've asked to relay for us, once AutoNAT tells us we're private.
+    let mut relay_addr: Option<Multiaddr> = None;
+    let mut connected_peers: HashSet<PeerId> = HashSet::new();
+    let mut peer_manager = PeerManager::new(opt.target_peers, PEER_EXCESS_FACTOR);
+
+    if let Some(rendezvous_addr) = &opt.rendezvous {
+        log::info!("dialing rendezvous point {rendezvous_addr}");
+        if let Err(err) = swarm.dial(rendezvous_addr.clone()) {
+            log::error!("dialing rendezvous point error: {err}");
+        }
+    }
+
+    let mut bandwidth_timer = Delay::new(BANDWIDTH_LOG_INTERVAL).fuse();
+    let mut last_totals = (0u64, 0u64);
+    let mut last_instant = Instant::now();
+
+    let mut identify_push_timer = Delay::new(network_load.identify_push_interval).fuse();
 
     loop {
-        let event = swarm.select_next_some().await;
+        let event = select! {
+            event = swarm.select_next_some() => event,
+            _ = bandwidth_timer => {
+                let sinks = swarm.behaviour().bandwidth();
+                let inbound = sinks.total_inbound();
+                let outbound = sinks.total_outbound();
+                let elapsed = last_instant.elapsed().as_secs_f64();
+                let in_rate = (inbound - last_totals.0) as f64 / elapsed;
+                let out_rate = (outbound - last_totals.1) as f64 / elapsed;
+                log::info!(
+                    "bandwidth: {inbound} B in / {outbound} B out total ({in_rate:.0} B/s in, {out_rate:.0} B/s out)"
+                );
+                last_totals = (inbound, outbound);
+                last_instant = Instant::now();
+                bandwidth_timer = Delay::new(BANDWIDTH_LOG_INTERVAL).fuse();
+                continue;
+            }
+            _ = identify_push_timer => {
+                if !connected_peers.is_empty() {
+                    log::info!("pushing identify info to {} connected peer(s)", connected_peers.len());
+                    swarm.behaviour_mut().identify.push(connected_peers.iter().copied());
+                }
+                identify_push_timer = Delay::new(network_load.identify_push_interval).fuse();
+                continue;
+            }
+        };
 
         match handle_event(&opt, &mut swarm, event)? {
             NodeEvent::NewListenAddr(_) => {
@@ -104,6 +255,136 @@ async fn main() -> Result<(), Box<dyn Error>> {
                     }
                 }
             }
+            NodeEvent::NatPrivate => {
+                if relay_addr.is_some() {
+                    continue;
+                }
+
+                let Some(relay) = opt.peers.first() else {
+                    log::warn!("NAT status is private but no --peer was given to relay through");
+                    continue;
+                };
+
+                let Some(relay_peer_id) = peer_id_of(relay) else {
+                    log::warn!(
+                        "NAT status is private but --peer {relay} has no /p2p/<peer id>, cannot use it as a relay"
+                    );
+                    continue;
+                };
+
+                relay_addr = Some(relay.clone());
+
+                if connected_peers.contains(&relay_peer_id) {
+                    // Reaching a private verdict requires probing through already-connected
+                    // peers, so the relay is typically connected by now; request the
+                    // reservation directly instead of opening a redundant second connection.
+                    let circuit_addr = relay.clone().with(Protocol::P2pCircuit);
+                    log::info!(
+                        "NAT status is private, already connected to relay, requesting reservation on {circuit_addr}"
+                    );
+
+                    if let Err(err) = swarm.listen_on(circuit_addr) {
+                        log::error!("listening on relay circuit address error: {err}");
+                    }
+                } else {
+                    log::info!("NAT status is private, dialing {relay} to use as a relay");
+
+                    if let Err(err) = swarm.dial(relay.clone()) {
+                        log::error!("dialing relay error: {err}");
+                    }
+                }
+            }
+            NodeEvent::PeerConnected(peer_id, connection_id, inbound) => {
+                connected_peers.insert(peer_id);
+                peer_manager.on_connected(peer_id, connection_id, inbound);
+
+                let connected = peer_manager.len();
+                let to_prune = peer_manager.peers_to_prune();
+                if !to_prune.is_empty() {
+                    log::info!(
+                        "{} connected peers over target {}, pruning {}",
+                        connected,
+                        opt.target_peers,
+                        to_prune.len()
+                    );
+                }
+                for (prune_peer_id, prune_connection_id) in to_prune {
+                    log::info!("pruning inactive inbound peer {prune_peer_id}");
+                    swarm.close_connection(prune_connection_id);
+                }
+
+                let is_relay = relay_addr
+                    .as_ref()
+                    .and_then(peer_id_of)
+                    .map_or(false, |relay_peer_id| relay_peer_id == peer_id);
+
+                if is_relay {
+                    let relay = relay_addr.clone().expect("checked above");
+                    let circuit_addr = relay.with(Protocol::P2pCircuit);
+                    log::info!("connected to relay, requesting reservation on {circuit_addr}");
+
+                    if let Err(err) = swarm.listen_on(circuit_addr) {
+                        log::error!("listening on relay circuit address error: {err}");
+                    }
+                }
+
+                let is_rendezvous = opt
+                    .rendezvous
+                    .as_ref()
+                    .and_then(peer_id_of)
+                    .map_or(false, |rendezvous_peer_id| rendezvous_peer_id == peer_id);
+
+                if is_rendezvous {
+                    let namespace = rendezvous::Namespace::new(opt.namespace.clone())?;
+                    log::info!("connected to rendezvous point, registering under '{namespace}'");
+
+                    if let Err(err) =
+                        swarm
+                            .behaviour_mut()
+                            .rendezvous
+                            .register(namespace.clone(), peer_id, None)
+                    {
+                        log::error!("rendezvous register error: {err}");
+                    }
+
+                    if matches!(opt.command, Some(Command::ListPeers)) {
+                        log::info!("discovering peers under '{namespace}'");
+                        swarm.behaviour_mut().rendezvous.discover(
+                            Some(namespace),
+                            None,
+                            None,
+                            peer_id,
+                        );
+                    }
+                }
+            }
+            NodeEvent::Discovered(peers) => {
+                if peers.is_empty() {
+                    log::info!("no peers discovered under '{}'", opt.namespace);
+                } else {
+                    for (peer_id, addresses) in peers {
+                        let addresses = addresses
+                            .iter()
+                            .map(Multiaddr::to_string)
+                            .collect::<Vec<_>>()
+                            .join(", ");
+                        println!("{peer_id}\t{addresses}");
+                    }
+                }
+                // The registration records carry every address the peer advertised to the
+                // rendezvous point, so there's nothing further to wait on before exiting.
+                process::exit(0);
+            }
+            NodeEvent::Identified(peer_id) => {
+                peer_manager.note_activity(&peer_id);
+            }
+            NodeEvent::PeerDisconnected(peer_id) => {
+                connected_peers.remove(&peer_id);
+                peer_manager.on_disconnected(&peer_id);
+            }
+            NodeEvent::Activity(peer_id) => {
+                peer_manager.note_activity(&peer_id);
+            }
             NodeEvent::None => {}
         }
     }
@@ -112,6 +393,20 @@ async fn main() -> Result<(), Box<dyn Error>> {
 enum NodeEvent {
     None,
     NewListenAddr(Multiaddr),
+    NatPrivate,
+    PeerConnected(PeerId, ConnectionId, bool),
+    PeerDisconnected(PeerId),
+    Discovered(Vec<(PeerId, Vec<Multiaddr>)>),
+    Identified(PeerId),
+    Activity(PeerId),
+}
+
+/// Extracts the trailing `/p2p/<peer id>` component of a multiaddr, if present.
+fn peer_id_of(addr: &Multiaddr) -> Option<PeerId> {
+    addr.iter().find_map(|p| match p {
+        Protocol::P2p(hash) => PeerId::from_multihash(hash).ok(),
+        _ => None,
+    })
 }
 
 fn handle_event<E: std::fmt::Debug>(
@@ -125,21 +420,79 @@ fn handle_event<E: std::fmt::Debug>(
         // Print out our listen address
         SwarmEvent::NewListenAddr { address, .. } => return Ok(NodeEvent::NewListenAddr(address)),
 
+        SwarmEvent::ConnectionEstablished {
+            peer_id,
+            connection_id,
+            endpoint,
+            ..
+        } => {
+            return Ok(NodeEvent::PeerConnected(
+                peer_id,
+                connection_id,
+                endpoint.is_listener(),
+            ))
+        }
+        SwarmEvent::ConnectionClosed { peer_id, .. } => {
+            return Ok(NodeEvent::PeerDisconnected(peer_id))
+        }
+
         // Identify
         SwarmEvent::Behaviour(Event::Identify(IdentifyEvent::Received { peer_id, info })) => {
             log::info!(
                 "Identify info from {peer_id:?}: observed address {:?}",
                 info.observed_addr
             );
+            return Ok(NodeEvent::Identified(peer_id));
         }
 
         // AutoNAT
         SwarmEvent::Behaviour(Event::AutoNat(AutoNatEvent::InboundProbe(
-            e @ InboundProbeEvent::Request { .. },
+            InboundProbeEvent::Request { peer, .. },
         ))) => {
-            log::info!("AutoNAT InboundProbeEvent: {e:?}");
+            log::info!("AutoNAT InboundProbeEvent::Request from {peer}");
+            return Ok(NodeEvent::Activity(peer));
+        }
+        SwarmEvent::Behaviour(Event::AutoNat(AutoNatEvent::StatusChanged { old, new })) => {
+            log::info!("AutoNAT status changed from {old:?} to {new:?}");
+
+            if matches!(new, NatStatus::Private) {
+                return Ok(NodeEvent::NatPrivate);
+            }
         }
 
+        // Relay client: making a reservation and relaying our traffic
+        SwarmEvent::Behaviour(Event::RelayClient(
+            relay::client::Event::ReservationReqAccepted { relay_peer_id, .. },
+        )) => {
+            log::info!("relay reservation accepted by {relay_peer_id}");
+        }
+        SwarmEvent::Behaviour(Event::RelayClient(event)) => {
+            log::info!("relay client event: {event:?}");
+        }
+
+        // DCUtR: upgrading a relayed connection to a direct one
+        SwarmEvent::Behaviour(Event::Dcutr(event)) => {
+            log::info!("DCUtR event: {event:?}");
+        }
+
+        // Rendezvous: registering ourselves and discovering peers in a namespace
+        SwarmEvent::Behaviour(Event::Rendezvous(rendezvous::client::Event::Discovered {
+            registrations,
+            ..
+        })) => {
+            let peers = registrations
+                .into_iter()
+                .map(|r| (r.record.peer_id(), r.record.addresses().to_vec()))
+                .collect();
+            return Ok(NodeEvent::Discovered(peers));
+        }
+        SwarmEvent::Behaviour(Event::Rendezvous(event)) => {
+            log::info!("rendezvous event: {event:?}");
+        }
+
+        // ConnectionLimits never emits an event; it only ever denies dials/connections inline.
+        SwarmEvent::Behaviour(Event::ConnectionLimits(void)) => void::unreachable(void),
+
         // Ignore the rest
         _ => {}
     }
@@ -152,11 +505,23 @@ fn handle_event<E: std::fmt::Debug>(
 struct Behaviour {
     auto_nat: libp2p::autonat::Behaviour,
     identify: libp2p::identify::Behaviour,
-    keep_alive: keep_alive::Behaviour,
+    relay_client: relay::client::Behaviour,
+    dcutr: dcutr::Behaviour,
+    rendezvous: rendezvous::client::Behaviour,
+    connection_limits: connection_limits::Behaviour,
+    #[behaviour(ignore)]
+    bandwidth: Arc<bandwidth::BandwidthSinks>,
 }
 
 impl Behaviour {
-    fn new(local_public_key: identity::PublicKey) -> Self {
+    fn new(
+        local_key: &identity::Keypair,
+        relay_client: relay::client::Behaviour,
+        bandwidth: Arc<bandwidth::BandwidthSinks>,
+        network_load: NetworkLoad,
+        connection_limits: ConnectionLimits,
+    ) -> Self {
+        let local_public_key = local_key.public();
         let peer_id = PeerId::from(local_public_key.clone());
 
         Self {
@@ -164,9 +529,9 @@ impl Behaviour {
                 peer_id,
                 libp2p::autonat::Config {
                     only_global_ips: false,
-                    boot_delay: Duration::from_secs(3),
+                    boot_delay: network_load.autonat_boot_delay,
                     timeout: Duration::from_secs(301),
-                    throttle_server_period: Duration::from_secs(15),
+                    throttle_server_period: network_load.autonat_throttle_server_period,
                     ..Default::default()
                 },
             ),
@@ -174,9 +539,18 @@ impl Behaviour {
                 "/safe/0.1.0".into(),
                 local_public_key,
             )),
-            keep_alive: keep_alive::Behaviour::default(),
+            relay_client,
+            dcutr: dcutr::Behaviour::new(peer_id),
+            rendezvous: rendezvous::client::Behaviour::new(local_key.clone()),
+            connection_limits: connection_limits::Behaviour::new(connection_limits),
+            bandwidth,
         }
     }
+
+    /// The shared bandwidth counters for the transport backing this behaviour's swarm.
+    fn bandwidth(&self) -> &Arc<bandwidth::BandwidthSinks> {
+        &self.bandwidth
+    }
 }
 
 #[derive(Debug)]
@@ -184,7 +558,10 @@ impl Behaviour {
 enum Event {
     AutoNat(libp2p::autonat::Event),
     Identify(libp2p::identify::Event),
-    KeepAlive(Void),
+    RelayClient(relay::client::Event),
+    Dcutr(dcutr::Event),
+    Rendezvous(rendezvous::client::Event),
+    ConnectionLimits(Void),
 }
 
 impl From<libp2p::autonat::Event> for Event {
@@ -197,8 +574,23 @@ impl From<libp2p::identify::Event> for Event {
         Self::Identify(v)
     }
 }
+impl From<relay::client::Event> for Event {
+    fn from(v: relay::client::Event) -> Self {
+        Self::RelayClient(v)
+    }
+}
+impl From<dcutr::Event> for Event {
+    fn from(v: dcutr::Event) -> Self {
+        Self::Dcutr(v)
+    }
+}
+impl From<rendezvous::client::Event> for Event {
+    fn from(v: rendezvous::client::Event) -> Self {
+        Self::Rendezvous(v)
+    }
+}
 impl From<Void> for Event {
     fn from(v: Void) -> Self {
-        Self::KeepAlive(v)
+        Self::ConnectionLimits(v)
     }
 }
@@ -0,0 +1,77 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Maps the `--network-load` CLI knob (1 = minimal churn, 5 = always warm) onto the concrete
+//! `Duration`s that drive connection and probe aggressiveness throughout the example.
+
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy)]
+pub struct NetworkLoad {
+    /// How long an idle connection is kept open before the swarm closes it.
+    pub idle_connection_timeout: Duration,
+    /// AutoNAT: delay before the first dial-back probe is attempted.
+    pub autonat_boot_delay: Duration,
+    /// AutoNAT: minimum time before the same peer is asked again to serve one of our probes.
+    pub autonat_throttle_server_period: Duration,
+    /// How often we actively push our identify info to connected peers.
+    pub identify_push_interval: Duration,
+}
+
+impl From<u8> for NetworkLoad {
+    fn from(level: u8) -> Self {
+        match level {
+            1 => NetworkLoad {
+                idle_connection_timeout: Duration::from_secs(1),
+                autonat_boot_delay: Duration::from_secs(15),
+                autonat_throttle_server_period: Duration::from_secs(120),
+                identify_push_interval: Duration::from_secs(900),
+            },
+            2 => NetworkLoad {
+                idle_connection_timeout: Duration::from_secs(5),
+                autonat_boot_delay: Duration::from_secs(10),
+                autonat_throttle_server_period: Duration::from_secs(60),
+                identify_push_interval: Duration::from_secs(600),
+            },
+            3 => NetworkLoad {
+                idle_connection_timeout: Duration::from_secs(30),
+                autonat_boot_delay: Duration::from_secs(3),
+                autonat_throttle_server_period: Duration::from_secs(15),
+                identify_push_interval: Duration::from_secs(300),
+            },
+            4 => NetworkLoad {
+                idle_connection_timeout: Duration::from_secs(120),
+                autonat_boot_delay: Duration::from_secs(2),
+                autonat_throttle_server_period: Duration::from_secs(10),
+                identify_push_interval: Duration::from_secs(120),
+            },
+            5 => NetworkLoad {
+                idle_connection_timeout: Duration::from_secs(600),
+                autonat_boot_delay: Duration::from_secs(1),
+                autonat_throttle_server_period: Duration::from_secs(5),
+                identify_push_interval: Duration::from_secs(60),
+            },
+            other => {
+                log::warn!("network-load {other} out of range 1..=5, falling back to 3");
+                NetworkLoad::from(3)
+            }
+        }
+    }
+}
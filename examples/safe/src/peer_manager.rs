@@ -0,0 +1,108 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Tracks connected peers beyond the static [`ConnectionLimits`](libp2p::connection_limits) and
+//! prunes the least-useful ones once the peer count grows past `target_peers` by more than
+//! `excess_factor`.
+
+use libp2p::swarm::ConnectionId;
+use libp2p::PeerId;
+use std::collections::HashMap;
+use std::time::Instant;
+
+struct PeerInfo {
+    connection_id: ConnectionId,
+    inbound: bool,
+    last_activity: Instant,
+}
+
+pub struct PeerManager {
+    target_peers: usize,
+    excess_factor: f64,
+    peers: HashMap<PeerId, PeerInfo>,
+}
+
+impl PeerManager {
+    pub fn new(target_peers: usize, excess_factor: f64) -> Self {
+        Self {
+            target_peers,
+            excess_factor,
+            peers: HashMap::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.peers.len()
+    }
+
+    pub fn on_connected(&mut self, peer_id: PeerId, connection_id: ConnectionId, inbound: bool) {
+        self.peers.insert(
+            peer_id,
+            PeerInfo {
+                connection_id,
+                inbound,
+                last_activity: Instant::now(),
+            },
+        );
+    }
+
+    pub fn on_disconnected(&mut self, peer_id: &PeerId) {
+        self.peers.remove(peer_id);
+    }
+
+    pub fn note_activity(&mut self, peer_id: &PeerId) {
+        if let Some(info) = self.peers.get_mut(peer_id) {
+            info.last_activity = Instant::now();
+        }
+    }
+
+    /// The inbound peers to close connections to, longest-inactive first, so that the connected
+    /// peer count drops back down to `target_peers` plus the `excess_factor` allowance. Outbound
+    /// peers are never selected, so the count stays above that limit when there are too few
+    /// inbound peers.
+    ///
+    /// Selected peers are removed immediately, so a connection whose close is still in flight
+    /// isn't reselected by a later call before its `ConnectionClosed` event arrives.
+    pub fn peers_to_prune(&mut self) -> Vec<(PeerId, ConnectionId)> {
+        let limit = (self.target_peers as f64 * (1.0 + self.excess_factor)) as usize;
+        if self.peers.len() <= limit {
+            return Vec::new();
+        }
+
+        let mut inbound_idle: Vec<(PeerId, Instant)> = self
+            .peers
+            .iter()
+            .filter(|(_, info)| info.inbound)
+            .map(|(peer_id, info)| (*peer_id, info.last_activity))
+            .collect();
+        inbound_idle.sort_by_key(|(_, last_activity)| *last_activity);
+
+        let excess = self.peers.len() - limit;
+        inbound_idle
+            .into_iter()
+            .take(excess)
+            .filter_map(|(peer_id, _)| {
+                self.peers
+                    .remove(&peer_id)
+                    .map(|info| (peer_id, info.connection_id))
+            })
+            .collect()
+    }
+}